@@ -0,0 +1,464 @@
+//! A narrow, typed wrapper around `rusqlite::Connection`.
+//!
+//! `ReportDatabase` used to call `connection.execute` with raw SQL strings
+//! scattered through its methods. `Db` is the single place that knows the
+//! schema and the SQL that goes with it; everything above it talks in terms
+//! of `TagId`/`TransferId` and plain Rust arguments. SQLite only allows one
+//! writer at a time anyway, so there is no point making any of this async.
+
+use crate::parser::{Entry, Span};
+use crate::Config;
+use rusqlite::backup::{Backup, StepResult};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::Duration;
+
+/// Pages copied per `Backup::step`, and how long to sleep between steps so
+/// a long backup doesn't starve the writer holding the source connection.
+const BACKUP_STEP_PAGES: i32 = 100;
+const BACKUP_STEP_SLEEP: Duration = Duration::from_millis(250);
+
+/// How many consecutive `Busy`/`Locked` steps to retry (each after a
+/// `BACKUP_STEP_SLEEP` sleep) before giving up on the backup.
+const BACKUP_MAX_RETRIES: u32 = 20;
+
+pub(crate) type TagId = i64;
+pub(crate) type TransferId = i64;
+
+pub(crate) struct Db {
+    connection: rusqlite::Connection,
+}
+
+impl Db {
+    pub(crate) fn open(connection: rusqlite::Connection) -> rusqlite::Result<Self> {
+        connection.set_db_config(
+            rusqlite::config::DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY,
+            true,
+        )?;
+
+        let db = Self { connection };
+        db.create_tables()?;
+        Ok(db)
+    }
+
+    fn create_tables(&self) -> rusqlite::Result<()> {
+        self.connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS tags(
+                tagid INTEGER PRIMARY KEY,
+                name TEXT UNIQUE
+            );
+            CREATE TABLE IF NOT EXISTS transfers(
+                transferid INTEGER PRIMARY KEY,
+                store TEXT,
+                amount INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS tagged_transfers(
+                tagid INTEGER,
+                transferid INTEGER,
+                FOREIGN KEY(tagid) REFERENCES tags(tagid) ON DELETE CASCADE,
+                FOREIGN KEY(transferid) REFERENCES transfers(transferid) ON DELETE CASCADE
+            );
+            CREATE UNIQUE INDEX IF NOT EXISTS tagged_transfers_lookup
+                ON tagged_transfers(tagid, transferid);",
+        )
+    }
+
+    /// Imports every entry in a single transaction, reusing one tag-name
+    /// cache across the whole batch instead of re-resolving a tag's id for
+    /// every row it appears on. The `INSERT`s themselves go through the
+    /// `insert_tag_row`/`insert_transfer_row`/`link_row` helpers (each
+    /// backed by a `prepare_cached` statement, so repeating them per row
+    /// costs a hashmap lookup rather than a fresh `prepare`), so this
+    /// stays the single place that SQL is written.
+    ///
+    /// Each entry is paired with the line it was parsed from, since its
+    /// tag `Span`s are only offsets into that line's source text.
+    pub(crate) fn import_entries<'s>(
+        &mut self,
+        config: &Config,
+        entries: impl IntoIterator<Item = (Entry, &'s str)>,
+    ) -> rusqlite::Result<()> {
+        let tx = self.connection.transaction()?;
+        let mut tag_cache: HashMap<Box<str>, TagId> = HashMap::new();
+
+        for (entry, line) in entries {
+            let amount = i64::try_from(entry.amount).map_err(|_| {
+                rusqlite::Error::ToSqlConversionFailure(
+                    format!(
+                        "transfer amount {} does not fit in the `amount` column",
+                        entry.amount
+                    )
+                    .into(),
+                )
+            })?;
+            let transfer = insert_transfer_row(&tx, &entry.store, amount)?;
+
+            for path in &entry.tags {
+                for name in resolve_tag_names(config, path, line) {
+                    let tag = match tag_cache.get(name.as_str()) {
+                        Some(&id) => id,
+                        None => {
+                            let id = insert_tag_row(&tx, &name)?;
+                            tag_cache.insert(Box::from(name.as_str()), id);
+                            id
+                        }
+                    };
+
+                    link_row(&tx, tag, transfer)?;
+                }
+            }
+        }
+
+        tx.commit()
+    }
+
+    /// Sum of `transfers.amount` grouped by tag name, restricted to `tags`.
+    /// An empty slice matches no tags and returns no rows.
+    pub(crate) fn sum_by_tag(&self, tags: &[&str]) -> rusqlite::Result<Vec<(String, i64)>> {
+        if tags.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let sql = format!(
+            "SELECT tags.name, SUM(transfers.amount)
+             FROM tagged_transfers
+             JOIN tags ON tags.tagid = tagged_transfers.tagid
+             JOIN transfers ON transfers.transferid = tagged_transfers.transferid
+             WHERE tags.name IN ({})
+             GROUP BY tags.name",
+            placeholders(tags.len()),
+        );
+
+        self.query_rows(&sql, &to_sql_params(tags))
+    }
+
+    /// Sum of `transfers.amount` grouped by store. `None` sums every
+    /// transfer; `Some(tags)` restricts to those carrying at least one of
+    /// `tags` (an empty slice then matches nothing, rather than matching
+    /// everything the way a bare empty-slice parameter would).
+    pub(crate) fn totals_by_store(
+        &self,
+        tags: Option<&[&str]>,
+    ) -> rusqlite::Result<Vec<(String, i64)>> {
+        let tags = match tags {
+            None => {
+                return self.query_rows(
+                    "SELECT store, SUM(amount) FROM transfers GROUP BY store",
+                    &[],
+                )
+            }
+            Some([]) => return Ok(Vec::new()),
+            Some(tags) => tags,
+        };
+
+        // A transfer tagged with more than one of `tags` must still only
+        // contribute its amount once, hence the `IN (SELECT DISTINCT ...)`
+        // rather than joining tagged_transfers/tags directly (which would
+        // double-count it per matching tag).
+        let sql = format!(
+            "SELECT store, SUM(amount)
+             FROM transfers
+             WHERE transferid IN (
+                 SELECT DISTINCT tagged_transfers.transferid
+                 FROM tagged_transfers
+                 JOIN tags ON tags.tagid = tagged_transfers.tagid
+                 WHERE tags.name IN ({})
+             )
+             GROUP BY store",
+            placeholders(tags.len()),
+        );
+
+        self.query_rows(&sql, &to_sql_params(tags))
+    }
+
+    /// Copies a live, consistent snapshot of this database to `dest`,
+    /// page by page, via SQLite's online backup API. `progress`, if given,
+    /// is invoked with `(remaining, total)` pages after every step.
+    ///
+    /// `Busy`/`Locked` steps (the source busy writing, or a conflicting
+    /// lock elsewhere) are retried up to `BACKUP_MAX_RETRIES` times rather
+    /// than treated like an ordinary "more pages to go" step; past that,
+    /// the backup gives up and returns an error instead of looping forever.
+    pub(crate) fn backup_to(
+        &self,
+        dest: &Path,
+        progress: Option<fn(remaining: usize, total: usize)>,
+    ) -> rusqlite::Result<()> {
+        let mut dest_connection = rusqlite::Connection::open(dest)?;
+        let backup = Backup::new(&self.connection, &mut dest_connection)?;
+        let mut retries_left = BACKUP_MAX_RETRIES;
+
+        loop {
+            let step_result = backup.step(BACKUP_STEP_PAGES)?;
+
+            if let Some(progress) = progress {
+                let p = backup.progress();
+                progress(p.remaining as usize, p.pagecount as usize);
+            }
+
+            match step_result {
+                StepResult::Done => return Ok(()),
+                StepResult::More => retries_left = BACKUP_MAX_RETRIES,
+                StepResult::Busy | StepResult::Locked => {
+                    if retries_left == 0 {
+                        return Err(backup_retry_exhausted_error(step_result));
+                    }
+                    retries_left -= 1;
+                }
+                _ => (),
+            }
+
+            std::thread::sleep(BACKUP_STEP_SLEEP);
+        }
+    }
+
+    /// Runs `sql` and collects every row into a `Vec<T>`.
+    pub(crate) fn query_rows<T: FromRow>(
+        &self,
+        sql: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> rusqlite::Result<Vec<T>> {
+        self.connection
+            .prepare_cached(sql)?
+            .query_map(params, T::from_row)?
+            .collect()
+    }
+}
+
+/// Builds the error `Db::backup_to` returns once it gives up retrying a
+/// `Busy`/`Locked` backup step, mirroring how rusqlite's own
+/// `Connection::backup`/`restore` turn a raw SQLite result code into an
+/// `Error::SqliteFailure`.
+fn backup_retry_exhausted_error(step_result: StepResult) -> rusqlite::Error {
+    let code = match step_result {
+        StepResult::Busy => rusqlite::ffi::SQLITE_BUSY,
+        StepResult::Locked => rusqlite::ffi::SQLITE_LOCKED,
+        _ => unreachable!("only called for Busy/Locked step results"),
+    };
+
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(code),
+        Some(format!(
+            "backup gave up after retrying a busy/locked step {} times in a row",
+            BACKUP_MAX_RETRIES
+        )),
+    )
+}
+
+/// Deserializes one result row into a typed value, by position.
+///
+/// Blanket-implemented for tuples of types that are themselves
+/// `rusqlite::types::FromSql`, so callers can write
+/// `db.query_rows::<(String, i64)>(...)` instead of hand-rolling a
+/// `row.get(0)?, row.get(1)?, ...` extraction for every query.
+pub(crate) trait FromRow: Sized {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self>;
+}
+
+macro_rules! impl_from_row {
+    ($($idx:tt => $t:ident),+) => {
+        impl<$($t),+> FromRow for ($($t,)+)
+        where
+            $($t: rusqlite::types::FromSql),+
+        {
+            fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+                Ok(($(row.get($idx)?,)+))
+            }
+        }
+    };
+}
+
+impl_from_row!(0 => A);
+impl_from_row!(0 => A, 1 => B);
+impl_from_row!(0 => A, 1 => B, 2 => C);
+impl_from_row!(0 => A, 1 => B, 2 => C, 3 => D);
+
+/// Looks up `name` in `tags`, inserting it first if it isn't there yet.
+/// Takes `&rusqlite::Connection` rather than `&Db` so it can run against
+/// either a plain connection or a `Transaction` (which derefs to one).
+fn insert_tag_row(connection: &rusqlite::Connection, name: &str) -> rusqlite::Result<TagId> {
+    connection
+        .prepare_cached("INSERT INTO tags(name) VALUES (?1) ON CONFLICT(name) DO NOTHING")?
+        .execute(rusqlite::params![name])?;
+
+    connection
+        .prepare_cached("SELECT tagid FROM tags WHERE name = ?1")?
+        .query_row(rusqlite::params![name], |row| row.get(0))
+}
+
+fn insert_transfer_row(
+    connection: &rusqlite::Connection,
+    store: &str,
+    amount: i64,
+) -> rusqlite::Result<TransferId> {
+    connection
+        .prepare_cached("INSERT INTO transfers(store, amount) VALUES (?1, ?2)")?
+        .execute(rusqlite::params![store, amount])?;
+
+    Ok(connection.last_insert_rowid())
+}
+
+fn link_row(
+    connection: &rusqlite::Connection,
+    tag: TagId,
+    transfer: TransferId,
+) -> rusqlite::Result<()> {
+    connection
+        .prepare_cached("INSERT OR IGNORE INTO tagged_transfers(tagid, transferid) VALUES (?1, ?2)")?
+        .execute(rusqlite::params![tag, transfer])?;
+
+    Ok(())
+}
+
+/// `?1, ?2, ..., ?n` for an `IN (...)` clause with `n` bound tag names.
+fn placeholders(count: usize) -> String {
+    (1..=count)
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn to_sql_params<'a>(tags: &'a [&'a str]) -> Vec<&'a dyn rusqlite::ToSql> {
+    tags.iter().map(|tag| tag as &dyn rusqlite::ToSql).collect()
+}
+
+/// Resolves one `*`-prefixed path to the tag name(s) it actually denotes:
+/// an alias expands to every tag it stands for, anything else is taken as
+/// a literal (possibly dotted) tag name.
+fn resolve_tag_names(config: &Config, path: &[Span], source: &str) -> Vec<String> {
+    let name = path
+        .iter()
+        .map(|span| span.text(source))
+        .collect::<Vec<_>>()
+        .join(".");
+
+    match config.aliases.get(name.as_str()) {
+        Some(tag_indices) => tag_indices
+            .iter()
+            .map(|&index| config.tags[index as usize].to_string())
+            .collect(),
+        None => vec![name],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_memory_db() -> Db {
+        Db::open(rusqlite::Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn insert_tag_row_is_idempotent() {
+        let db = open_memory_db();
+
+        let first = insert_tag_row(&db.connection, "food").unwrap();
+        let second = insert_tag_row(&db.connection, "food").unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn insert_transfer_row_and_link_row_round_trip() {
+        let db = open_memory_db();
+
+        let tag = insert_tag_row(&db.connection, "food").unwrap();
+        let transfer = insert_transfer_row(&db.connection, "grocer", 1200).unwrap();
+        link_row(&db.connection, tag, transfer).unwrap();
+
+        let totals = db.sum_by_tag(&["food"]).unwrap();
+        assert_eq!(totals, vec![("food".to_string(), 1200)]);
+    }
+
+    #[test]
+    fn query_rows_deserializes_into_typed_tuples() {
+        let db = open_memory_db();
+        insert_transfer_row(&db.connection, "grocer", 1200).unwrap();
+        insert_transfer_row(&db.connection, "grocer", 800).unwrap();
+
+        let rows: Vec<(String, i64)> = db
+            .query_rows(
+                "SELECT store, amount FROM transfers ORDER BY amount",
+                &[],
+            )
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![("grocer".to_string(), 800), ("grocer".to_string(), 1200)]
+        );
+    }
+
+    fn empty_config() -> Config {
+        Config {
+            tags: Vec::new(),
+            aliases: HashMap::new(),
+            database_path: Box::from(std::path::Path::new("unused.db")),
+        }
+    }
+
+    #[test]
+    fn import_entries_rolls_back_whole_batch_on_error() {
+        let mut db = open_memory_db();
+        let config = empty_config();
+
+        let overflowing = Entry {
+            store: "whale".to_string(),
+            amount: u64::try_from(i64::MAX).unwrap() + 1,
+            tags: Vec::new(),
+        };
+        let valid = Entry {
+            store: "grocer".to_string(),
+            amount: 1200,
+            tags: Vec::new(),
+        };
+
+        let result = db.import_entries(
+            &config,
+            vec![(valid, "grocer : 1200"), (overflowing, "whale : huge")],
+        );
+
+        assert!(result.is_err());
+
+        let rows: Vec<(String,)> = db.query_rows("SELECT store FROM transfers", &[]).unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn totals_by_store_does_not_double_count_multi_tagged_transfers() {
+        let db = open_memory_db();
+
+        let food = insert_tag_row(&db.connection, "food").unwrap();
+        let errands = insert_tag_row(&db.connection, "errands").unwrap();
+        let transfer = insert_transfer_row(&db.connection, "grocer", 1200).unwrap();
+        link_row(&db.connection, food, transfer).unwrap();
+        link_row(&db.connection, errands, transfer).unwrap();
+
+        let totals = db.totals_by_store(Some(&["food", "errands"])).unwrap();
+        assert_eq!(totals, vec![("grocer".to_string(), 1200)]);
+    }
+
+    #[test]
+    fn backup_to_copies_every_row_to_the_destination_file() {
+        let db = open_memory_db();
+        insert_transfer_row(&db.connection, "grocer", 1200).unwrap();
+
+        let dest = std::env::temp_dir().join(format!(
+            "diurne_backup_to_test_{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&dest);
+
+        db.backup_to(&dest, None).unwrap();
+
+        let restored = Db::open(rusqlite::Connection::open(&dest).unwrap()).unwrap();
+        let rows: Vec<(String, i64)> = restored
+            .query_rows("SELECT store, amount FROM transfers", &[])
+            .unwrap();
+        assert_eq!(rows, vec![("grocer".to_string(), 1200)]);
+
+        let _ = std::fs::remove_file(&dest);
+    }
+}
@@ -7,6 +7,13 @@ use std::{
 };
 use thiserror::Error;
 
+mod db;
+mod parser;
+mod reports;
+
+use db::Db;
+pub use reports::{ReportError, TagFilter};
+
 fn main() -> anyhow::Result<()> {
     let matches = clap::App::new("diurne")
         .version("0.1")
@@ -19,6 +26,20 @@ fn main() -> anyhow::Result<()> {
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            clap::Arg::with_name("import")
+                .long("import")
+                .help("parse and import transfer entries from PATH")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("backup")
+                .long("backup")
+                .help("snapshot the report database to PATH and exit")
+                .value_name("PATH")
+                .takes_value(true),
+        )
         .get_matches();
 
     let config_path = &Path::new(matches.value_of("config").unwrap());
@@ -26,12 +47,52 @@ fn main() -> anyhow::Result<()> {
     let config = validate_config(config_path, parsed_config).context("invalid config.")?;
 
     println!("config {:#?}", config);
-    let database =
+    let mut database =
         ReportDatabase::with_config(&config).context("failed to open report database.")?;
 
+    if let Some(import_path) = matches.value_of("import") {
+        import_entries_from_file(&mut database, &config, Path::new(import_path))
+            .context("failed to import transfer entries.")?;
+    }
+
+    if let Some(backup_path) = matches.value_of("backup") {
+        database
+            .backup_to(Path::new(backup_path), Some(report_backup_progress))
+            .context("failed to back up the report database.")?;
+    }
+
     Ok(())
 }
 
+/// Parses every non-blank line of `path` as a transfer entry (the format
+/// `Parser` expects, one entry per line) and imports the whole batch in a
+/// single transaction.
+fn import_entries_from_file(
+    database: &mut ReportDatabase,
+    config: &Config,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let content = fs::read_to_string(path).context("failed to read entries file.")?;
+
+    let entries = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            parser::Parser::new(line)
+                .parse_entry()
+                .map(|entry| (entry, line))
+                .with_context(|| format!("failed to parse entry on line {}", number + 1))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(database.import_entries(config, entries)?)
+}
+
+fn report_backup_progress(remaining: usize, total: usize) {
+    println!("backup: {}/{} pages remaining", remaining, total);
+}
+
 #[derive(Deserialize)]
 pub struct ParsedConfig {
     tags: Vec<String>,
@@ -42,8 +103,8 @@ pub type TagIndex = u8;
 
 #[derive(Debug)]
 pub struct Config {
-    tags: Vec<Box<str>>,
-    aliases: HashMap<Box<str>, Vec<TagIndex>>,
+    pub(crate) tags: Vec<Box<str>>,
+    pub(crate) aliases: HashMap<Box<str>, Vec<TagIndex>>,
     database_path: Box<Path>,
 }
 
@@ -93,52 +154,41 @@ fn validate_config(
 }
 
 pub struct ReportDatabase {
-    connection: rusqlite::Connection,
+    pub(crate) db: Db,
 }
 
 impl ReportDatabase {
     pub fn with_config(config: &Config) -> Result<Self, rusqlite::Error> {
         let connection = rusqlite::Connection::open(&config.database_path)?;
-        connection.set_db_config(
-            rusqlite::config::DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY,
-            true,
-        )?;
 
         Ok(ReportDatabase {
-            connection: Self::insert_tables(connection)?,
+            db: Db::open(connection)?,
         })
     }
 
-    fn insert_tables(
-        connection: rusqlite::Connection,
-    ) -> Result<rusqlite::Connection, rusqlite::Error> {
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS tags(
-                tagid INTEGER PRIMARY KEY,
-                name TEXT UNIQUE
-            );",
-            rusqlite::NO_PARAMS,
-        )?;
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS transfers(
-                transferid INTEGER PRIMARY KEY,
-                store TEXT,
-                amount INTEGER
-            );",
-            rusqlite::NO_PARAMS,
-        )?;
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS tagged_transfers(
-                tagid INTEGER,
-                transferid INTEGER,
-                FOREIGN KEY(tagid) REFERENCES tags(tagid) ON DELETE CASCADE,
-                FOREIGN KEY(transferid) REFERENCES transfers(transferid) ON DELETE CASCADE
-            );
-            CREATE UNIQUE INDEX IF NOT EXISTS tagged_transfers_lookup
-                ON tagged_transfers(tagid, transferid);",
-            rusqlite::NO_PARAMS,
-        )?;
-
-        Ok(connection)
+    /// Imports every parsed entry in a single transaction. Each entry must
+    /// be paired with the line it was parsed from, since its tag `Span`s
+    /// are only offsets into that line's source text.
+    ///
+    /// `*`-prefixed paths in each `Entry` are resolved against `config`:
+    /// first as an alias (expanding to its underlying tags), falling back
+    /// to the dotted path itself as a literal tag name.
+    pub fn import_entries<'s>(
+        &mut self,
+        config: &Config,
+        entries: impl IntoIterator<Item = (parser::Entry, &'s str)>,
+    ) -> rusqlite::Result<()> {
+        self.db.import_entries(config, entries)
+    }
+
+    /// Snapshots this database to `dest` while it keeps running, using
+    /// SQLite's online backup API rather than copying the file directly
+    /// (which could race a writer and copy a half-written file).
+    pub fn backup_to(
+        &self,
+        dest: &Path,
+        progress: Option<fn(remaining: usize, total: usize)>,
+    ) -> rusqlite::Result<()> {
+        self.db.backup_to(dest, progress)
     }
 }
@@ -1,4 +1,6 @@
-#[derive(Debug, Copy, Clone)]
+use thiserror::Error;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum TokenKind {
     Ident,
     Digits,
@@ -16,15 +18,22 @@ pub struct Span {
     end: u32,
 }
 
+impl Span {
+    pub(crate) fn text<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.start as usize..self.end as usize]
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 pub struct Token {
     kind: TokenKind,
     span: Span,
 }
 
-type SourceIter<'s> = std::iter::Peekable<std::str::CharIndices<'s>>
+type SourceIter<'s> = std::iter::Peekable<std::str::CharIndices<'s>>;
 
 pub struct Tokenizer<'s> {
-    source: SourceIter<'s>
+    source: SourceIter<'s>,
     column: u16,
     line: u16,
 }
@@ -34,13 +43,12 @@ impl<'s> Tokenizer<'s> {
         let iter = source.char_indices().peekable();
 
         Self {
-            source,
+            source: iter,
             column: 0,
             line: 0,
         }
     }
 
-
     fn scan_spaces(&mut self, start: u32) -> Token {
         let span = self.scan_until(start, |c| !c.is_whitespace());
         self.make_token(span, TokenKind::Spaces)
@@ -52,11 +60,11 @@ impl<'s> Tokenizer<'s> {
     }
 
     fn scan_digits(&mut self, start: u32) -> Token {
-        let span = self.scan_until(start, |c| !c.is_digit());
+        let span = self.scan_until(start, |c| !c.is_ascii_digit());
         self.make_token(span, TokenKind::Digits)
     }
 
-    fn scan_until(&self, start: u32, until: impl Fn(char) -> bool) -> std::ops::Range<u32> {
+    fn scan_until(&mut self, start: u32, until: impl Fn(char) -> bool) -> std::ops::Range<u32> {
         let mut last = start;
         while let Some((idx, c)) = self.peek() {
             if until(c) {
@@ -70,47 +78,51 @@ impl<'s> Tokenizer<'s> {
         start..(last + 1)
     }
 
-    fn peek(&self) -> Option<char> {
-        self.source.peek()
+    fn peek(&mut self) -> Option<(u32, char)> {
+        self.source.peek().map(|&(idx, c)| (idx as u32, c))
     }
 
-    fn bump(&self) -> Option<char> {
-        let c = self.source.next();
-        if c.is_none() {
-            return None;
-        }
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.source.next()?;
         self.column += 1;
 
-        if Self::is_break(c) => {
-            if c == '\r' => {
-                assert_eq!(Some('\n'), self.source.next());
+        if Self::is_break(c) {
+            if c == '\r' {
+                if let Some(&(_, '\n')) = self.source.peek() {
+                    self.source.next();
+                }
             }
-                
+
             self.line += 1;
             self.column = 0;
         }
 
         Some(c)
     }
-    
+
     fn make_token(&self, range: std::ops::Range<u32>, kind: TokenKind) -> Token {
         Token {
+            kind,
             span: Span {
                 column: self.column,
                 line: self.line,
                 start: range.start,
                 end: range.end,
-            }
+            },
         }
     }
 
+    fn is_ident(c: char) -> bool {
+        !c.is_whitespace() && c != ':' && c != '*' && c != '.'
+    }
+
     fn is_break(c: char) -> bool {
         match c {
             '\n' => true,
             '\r' => true,
             '\u{2028}' => true,
             '\u{2029}' => true,
-            _ => false
+            _ => false,
         }
     }
 }
@@ -119,33 +131,234 @@ impl Iterator for Tokenizer<'_> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Token> {
-        while let Some((start, c)) in self.peek() {
-            let start = start as u32;
-
-            let token = match c {
-                ':' => {
-                    let token = self.make_token(start..(start+1), TokenKind::Colon);
-                    self.bump();
-                    token
-                },
-                '*' => {
-                    let token = self.make_token(start..(start+1), TokenKind::Star);
-                    self.bump();
-                    token
-                },
-                '.' => {
-                    let token = self.make_token(start..(start+1), TokenKind::Dot);
-                    self.bump();
-                    token
-                },
-                c if c.is_whitespace() => self.scan_spaces(start),
-                c if c.is_digit() => self.scan_digits(start),
-                _ => self.scan_ident(start),
+        let (start, c) = self.peek()?;
+        let (line, column) = (self.line, self.column);
+
+        let mut token = match c {
+            ':' => {
+                let token = self.make_token(start..(start + 1), TokenKind::Colon);
+                self.bump();
+                token
+            }
+            '*' => {
+                let token = self.make_token(start..(start + 1), TokenKind::Star);
+                self.bump();
+                token
+            }
+            '.' => {
+                let token = self.make_token(start..(start + 1), TokenKind::Dot);
+                self.bump();
+                token
+            }
+            c if c.is_whitespace() => self.scan_spaces(start),
+            c if c.is_ascii_digit() => self.scan_digits(start),
+            _ => self.scan_ident(start),
+        };
+
+        // Scanning a multi-character token moves `line`/`column` past its
+        // end; the span reports where the token starts.
+        token.span.line = line;
+        token.span.column = column;
+
+        Some(token)
+    }
+}
+
+/// One parsed transfer line: a store, an amount, and the tags (or aliases)
+/// it was filed under. Each tag is kept as the `Span`s of its dot-separated
+/// path components rather than an already-joined string, so a later
+/// resolution pass can report precisely which segment failed to match a
+/// known tag or alias.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub store: String,
+    pub amount: u64,
+    pub tags: Vec<Vec<Span>>,
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("expected {expected:?} but found {found:?} at line {}, column {}", .span.line, .span.column)]
+    UnexpectedToken {
+        expected: TokenKind,
+        found: TokenKind,
+        span: Span,
+    },
+    #[error("missing ':' after store name at line {}, column {}", .span.line, .span.column)]
+    MissingColon { span: Span },
+    #[error("amount at line {}, column {} overflows a u64", .span.line, .span.column)]
+    AmountOverflow { span: Span },
+    #[error("unexpected trailing input at line {}, column {}", .span.line, .span.column)]
+    TrailingGarbage { span: Span },
+    #[error("unexpected end of input")]
+    UnexpectedEof,
+}
+
+/// Parses the one-line entry format produced by the `Tokenizer`:
+///
+/// ```text
+/// <store> : <amount> *<tag.path> *<tag.path> ...
+/// ```
+///
+/// `Spaces` tokens are skipped between every other token; everything else
+/// is kept so that `Span`s in the resulting `Entry` point back at the
+/// original source for error reporting.
+pub struct Parser<'s> {
+    source: &'s str,
+    tokens: std::iter::Peekable<Tokenizer<'s>>,
+}
+
+impl<'s> Parser<'s> {
+    pub fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            tokens: Tokenizer::new(source).peekable(),
+        }
+    }
+
+    pub fn parse_entry(&mut self) -> Result<Entry, ParseError> {
+        self.skip_spaces();
+        let store_token = self.expect(TokenKind::Ident)?;
+        let store = store_token.span.text(self.source).to_string();
+
+        self.skip_spaces();
+        self.expect_colon(store_token.span)?;
+
+        self.skip_spaces();
+        let amount_token = self.expect(TokenKind::Digits)?;
+        let amount = amount_token
+            .span
+            .text(self.source)
+            .parse::<u64>()
+            .map_err(|_| ParseError::AmountOverflow {
+                span: amount_token.span,
+            })?;
+
+        let mut tags = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.tokens.peek() {
+                Some(token) if token.kind == TokenKind::Star => {
+                    tags.push(self.parse_tag()?);
+                }
+                Some(token) => {
+                    return Err(ParseError::TrailingGarbage { span: token.span });
+                }
+                None => break,
+            }
+        }
+
+        Ok(Entry {
+            store,
+            amount,
+            tags,
+        })
+    }
+
+    fn parse_tag(&mut self) -> Result<Vec<Span>, ParseError> {
+        self.expect(TokenKind::Star)?;
+        let mut path = vec![self.expect(TokenKind::Ident)?.span];
+
+        while let Some(token) = self.tokens.peek() {
+            if token.kind != TokenKind::Dot {
+                break;
             }
+            self.tokens.next();
+            path.push(self.expect(TokenKind::Ident)?.span);
+        }
+
+        Ok(path)
+    }
+
+    fn expect_colon(&mut self, after: Span) -> Result<Span, ParseError> {
+        match self.tokens.next() {
+            Some(token) if token.kind == TokenKind::Colon => Ok(token.span),
+            Some(token) => Err(ParseError::MissingColon { span: token.span }),
+            None => Err(ParseError::MissingColon { span: after }),
+        }
+    }
+
+    fn expect(&mut self, expected: TokenKind) -> Result<Token, ParseError> {
+        match self.tokens.next() {
+            Some(token) if token.kind == expected => Ok(token),
+            Some(token) => Err(ParseError::UnexpectedToken {
+                expected,
+                found: token.kind,
+                span: token.span,
+            }),
+            None => Err(ParseError::UnexpectedEof),
+        }
+    }
 
-            return Some(token);
+    fn skip_spaces(&mut self) {
+        while let Some(token) = self.tokens.peek() {
+            if token.kind != TokenKind::Spaces {
+                break;
+            }
+            self.tokens.next();
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag_names(entry: &Entry, source: &str) -> Vec<String> {
+        entry
+            .tags
+            .iter()
+            .map(|path| {
+                path.iter()
+                    .map(|span| span.text(source))
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_store_amount_and_tags() {
+        let source = "grocer : 1200 *food.produce *errands";
+        let entry = Parser::new(source).parse_entry().unwrap();
+
+        assert_eq!(entry.store, "grocer");
+        assert_eq!(entry.amount, 1200);
+        assert_eq!(
+            tag_names(&entry, source),
+            vec!["food.produce".to_string(), "errands".to_string()]
+        );
+    }
+
+    #[test]
+    fn multi_segment_tag_path_keeps_every_span() {
+        let source = "grocer : 1200 *food.produce.organic";
+        let entry = Parser::new(source).parse_entry().unwrap();
 
-        None
+        assert_eq!(entry.tags.len(), 1);
+        assert_eq!(entry.tags[0].len(), 3);
+        assert_eq!(tag_names(&entry, source), vec!["food.produce.organic"]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn missing_colon_is_reported() {
+        let err = Parser::new("grocer 1200").parse_entry().unwrap_err();
+        assert!(matches!(err, ParseError::MissingColon { .. }));
+    }
+
+    #[test]
+    fn amount_overflowing_u64_is_reported() {
+        let err = Parser::new("grocer : 99999999999999999999999")
+            .parse_entry()
+            .unwrap_err();
+        assert!(matches!(err, ParseError::AmountOverflow { .. }));
+    }
+
+    #[test]
+    fn trailing_garbage_after_tags_is_reported() {
+        let err = Parser::new("grocer : 1200 *food not-a-tag")
+            .parse_entry()
+            .unwrap_err();
+        assert!(matches!(err, ParseError::TrailingGarbage { .. }));
+    }
+}
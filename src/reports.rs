@@ -0,0 +1,159 @@
+//! Aggregation queries over `transfers`/`tagged_transfers`.
+//!
+//! `ReportDatabase` is named for this: summing transfers by tag or by
+//! store, optionally narrowed down to a config alias instead of a literal
+//! list of tag names.
+
+use crate::{Config, ReportDatabase};
+use thiserror::Error;
+
+/// What to restrict an aggregation query to.
+pub enum TagFilter<'a> {
+    /// No restriction: every transfer counts.
+    All,
+    /// Only transfers tagged with one of these literal tag names.
+    Tags(&'a [&'a str]),
+    /// Only transfers tagged with one of the tags behind this config alias.
+    Alias(&'a str),
+}
+
+#[derive(Debug, Error)]
+pub enum TagFilterError {
+    #[error("unknown tag alias: {alias:?}")]
+    UnknownAlias { alias: String },
+}
+
+#[derive(Debug, Error)]
+pub enum ReportError {
+    #[error(transparent)]
+    UnknownAlias(#[from] TagFilterError),
+    #[error(transparent)]
+    Database(#[from] rusqlite::Error),
+}
+
+impl<'a> TagFilter<'a> {
+    /// Resolves this filter to the tag names it denotes, against `config`.
+    ///
+    /// `Ok(None)` means "no restriction" (`TagFilter::All`); `Ok(Some(tags))`
+    /// restricts to `tags`, which can legitimately be empty if an alias is
+    /// configured with no underlying tags — that still has to mean "match
+    /// nothing", not get treated the same as `All`. An `Alias` naming
+    /// anything outside `config.aliases` is an error rather than silently
+    /// resolving to "no restriction" (which would sum every transfer under
+    /// a mistyped alias name instead of reporting the typo).
+    fn resolve(&self, config: &'a Config) -> Result<Option<Vec<&'a str>>, TagFilterError> {
+        match self {
+            TagFilter::All => Ok(None),
+            TagFilter::Tags(tags) => Ok(Some(tags.to_vec())),
+            TagFilter::Alias(alias) => match config.aliases.get(*alias) {
+                Some(tag_indices) => Ok(Some(
+                    tag_indices
+                        .iter()
+                        .map(|&index| &*config.tags[index as usize])
+                        .collect(),
+                )),
+                None => Err(TagFilterError::UnknownAlias {
+                    alias: alias.to_string(),
+                }),
+            },
+        }
+    }
+}
+
+impl ReportDatabase {
+    /// Total amount transferred under each of `tags`, one row per tag that
+    /// has at least one transfer; tags with none get no row.
+    pub fn sum_by_tag(&self, tags: &[&str]) -> rusqlite::Result<Vec<(String, i64)>> {
+        self.db.sum_by_tag(tags)
+    }
+
+    /// Total amount transferred per store, optionally narrowed to `filter`.
+    pub fn totals_by_store(
+        &self,
+        config: &Config,
+        filter: TagFilter,
+    ) -> Result<Vec<(String, i64)>, ReportError> {
+        let tags = filter.resolve(config)?;
+        Ok(self.db.totals_by_store(tags.as_deref())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::parser::Entry;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn config_with_aliases() -> Config {
+        let mut aliases = HashMap::new();
+        aliases.insert(Box::from("shopping"), vec![0, 1]);
+        aliases.insert(Box::from("nothing"), vec![]);
+
+        Config {
+            tags: vec![Box::from("food"), Box::from("errands")],
+            aliases,
+            database_path: Box::from(Path::new("unused.db")),
+        }
+    }
+
+    fn memory_database() -> ReportDatabase {
+        ReportDatabase {
+            db: Db::open(rusqlite::Connection::open_in_memory().unwrap()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn alias_resolves_to_its_underlying_tags() {
+        let config = config_with_aliases();
+        let resolved = TagFilter::Alias("shopping").resolve(&config).unwrap();
+        assert_eq!(resolved, Some(vec!["food", "errands"]));
+    }
+
+    #[test]
+    fn all_resolves_to_no_restriction() {
+        let config = config_with_aliases();
+        assert_eq!(TagFilter::All.resolve(&config).unwrap(), None);
+    }
+
+    #[test]
+    fn alias_with_no_underlying_tags_matches_nothing_not_everything() {
+        let config = config_with_aliases();
+        let resolved = TagFilter::Alias("nothing").resolve(&config).unwrap();
+        assert_eq!(resolved, Some(Vec::new()));
+    }
+
+    #[test]
+    fn unknown_alias_is_an_error_not_an_empty_filter() {
+        let config = config_with_aliases();
+        let err = TagFilter::Alias("typo").resolve(&config).unwrap_err();
+        assert!(matches!(err, TagFilterError::UnknownAlias { .. }));
+    }
+
+    #[test]
+    fn totals_by_store_errors_on_unknown_alias_instead_of_summing_everything() {
+        let mut database = memory_database();
+        let config = config_with_aliases();
+
+        // A transfer must exist so an incorrect "no filter" fallback would
+        // show up as a nonzero total rather than an empty result either way.
+        database
+            .db
+            .import_entries(
+                &config,
+                vec![(
+                    Entry {
+                        store: "grocer".to_string(),
+                        amount: 1200,
+                        tags: Vec::new(),
+                    },
+                    "grocer : 1200",
+                )],
+            )
+            .unwrap();
+
+        let result = database.totals_by_store(&config, TagFilter::Alias("typo"));
+        assert!(matches!(result, Err(ReportError::UnknownAlias(_))));
+    }
+}